@@ -0,0 +1,245 @@
+//! Config-driven adapter/device/property declarations.
+//!
+//! Instead of hard-coding adapters and devices, the plugin loads a JSON or
+//! YAML file describing them up front, mirroring the register-table approach
+//! used by Modbus-over-MQTT bridges: every property names a topic, a payload
+//! `type`, an optional `scale` factor, and (for multi-word integers) a
+//! `swap_words` flag controlling decode byte/word order.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::error::Error;
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    pub adapters: HashMap<String, AdapterConfig>,
+    /// Maps an event name (`handleDeviceAdded`, `handleDeviceRemoved`,
+    /// `startPairing`, `cancelPairing`, `propertyChanged`) to an executable
+    /// run when that event fires. See [`crate::hooks`].
+    #[serde(default)]
+    pub hooks: crate::hooks::Hooks,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdapterConfig {
+    pub name: String,
+    #[serde(default)]
+    pub devices: HashMap<String, DeviceConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceConfig {
+    pub name: String,
+    #[serde(rename = "type", default = "default_device_type")]
+    pub typ: String,
+    #[serde(default)]
+    pub properties: HashMap<String, PropertyConfig>,
+}
+
+fn default_device_type() -> String {
+    "thing".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PropertyConfig {
+    /// Topic the property's current value is published to.
+    pub topic: String,
+    /// Topic to publish to when the gateway sets the property. Omit for
+    /// read-only properties.
+    #[serde(default)]
+    pub set_topic: Option<String>,
+    /// Topic to publish an (empty) request to in order to ask a
+    /// request/response device for its current value. If set, a scheduled
+    /// `period` refresh publishes here instead of just re-emitting whatever
+    /// was last seen on `topic`; the device's reply still arrives through the
+    /// normal `topic` subscription. Omit for devices that push state on their
+    /// own.
+    #[serde(default)]
+    pub request_topic: Option<String>,
+    #[serde(rename = "type")]
+    pub payload_type: PayloadType,
+    /// Multiplier applied to decoded numeric values, e.g. `0.1` to divide
+    /// the raw reading by 10, or `-1` to negate it.
+    #[serde(default)]
+    pub scale: Option<f64>,
+    /// Swaps the word order of multi-word integer payloads (`u32`/`s32`/`f64`).
+    #[serde(default)]
+    pub swap_words: bool,
+    /// How often to refresh this property, as a human duration like `"3s"`,
+    /// `"500ms"` or `"1m"`. Each refresh publishes a request to
+    /// `request_topic` if one is set, and otherwise re-emits the last value
+    /// seen on `topic`. Useful for properties fronted by a request/response
+    /// topic or a periodic status poll rather than an on-change push. Omit
+    /// for purely push-driven properties.
+    #[serde(default)]
+    pub period: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadType {
+    Bool,
+    U16,
+    S16,
+    U32,
+    S32,
+    F64,
+    String,
+}
+
+impl PayloadType {
+    /// Decodes a raw MQTT payload into a JSON value, applying `scale` to
+    /// numeric types and `swap_words` to the word order of multi-word ones.
+    pub fn decode(&self, payload: &[u8], scale: Option<f64>, swap_words: bool) -> Value {
+        let text = String::from_utf8_lossy(payload);
+        match self {
+            PayloadType::Bool => Value::Bool(parse_bool(&text)),
+            PayloadType::String => Value::String(text.into_owned()),
+            _ => {
+                let raw = decode_numeric(self, payload, &text, swap_words);
+                let scaled = raw * scale.unwrap_or(1.0);
+                serde_json::Number::from_f64(scaled)
+                    .map(Value::Number)
+                    .unwrap_or(Value::Null)
+            }
+        }
+    }
+}
+
+fn parse_bool(text: &str) -> bool {
+    matches!(text.trim(), "1" | "true" | "True" | "on" | "ON")
+}
+
+/// Decodes `payload` as the given numeric type. Prefers parsing it as plain
+/// text (the common case for human-readable sensor payloads); falls back to
+/// big-endian byte/word decoding for raw binary payloads.
+fn decode_numeric(typ: &PayloadType, payload: &[u8], text: &str, swap_words: bool) -> f64 {
+    if let Ok(n) = text.trim().parse::<f64>() {
+        return n;
+    }
+
+    let mut words: Vec<u16> = payload
+        .chunks(2)
+        .map(|chunk| {
+            if chunk.len() == 2 {
+                u16::from_be_bytes([chunk[0], chunk[1]])
+            } else {
+                chunk[0] as u16
+            }
+        })
+        .collect();
+    if swap_words {
+        words.reverse();
+    }
+
+    match typ {
+        PayloadType::U16 => words.get(0).copied().unwrap_or(0) as f64,
+        PayloadType::S16 => words.get(0).copied().unwrap_or(0) as i16 as f64,
+        PayloadType::U32 => join_words_u32(&words) as f64,
+        PayloadType::S32 => join_words_u32(&words) as i32 as f64,
+        PayloadType::F64 => f64::from_bits(join_words_u64(&words)),
+        PayloadType::Bool | PayloadType::String => unreachable!(),
+    }
+}
+
+fn join_words_u32(words: &[u16]) -> u32 {
+    let hi = *words.get(0).unwrap_or(&0) as u32;
+    let lo = *words.get(1).unwrap_or(&0) as u32;
+    (hi << 16) | lo
+}
+
+/// Joins the first four 16-bit words (big-endian, most-significant first)
+/// into a `u64`, as needed to decode a raw IEEE-754 double.
+fn join_words_u64(words: &[u16]) -> u64 {
+    (0..4).fold(0u64, |acc, i| {
+        (acc << 16) | *words.get(i).unwrap_or(&0) as u64
+    })
+}
+
+impl Config {
+    /// Loads a config from `path`, parsing it as YAML unless the extension
+    /// is `.json`.
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&text).map_err(|err| Error::ConfigParse(err.to_string())),
+            _ => serde_yaml::from_str(&text).map_err(|err| Error::ConfigParse(err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_bool_text() {
+        assert_eq!(PayloadType::Bool.decode(b"true", None, false), Value::Bool(true));
+        assert_eq!(PayloadType::Bool.decode(b"1", None, false), Value::Bool(true));
+        assert_eq!(PayloadType::Bool.decode(b"ON", None, false), Value::Bool(true));
+        assert_eq!(PayloadType::Bool.decode(b"0", None, false), Value::Bool(false));
+        assert_eq!(PayloadType::Bool.decode(b"off", None, false), Value::Bool(false));
+    }
+
+    #[test]
+    fn decodes_string_passthrough() {
+        assert_eq!(
+            PayloadType::String.decode(b"hello", None, false),
+            Value::String("hello".to_string())
+        );
+    }
+
+    #[test]
+    fn decodes_text_numbers() {
+        assert_eq!(PayloadType::U16.decode(b"123", None, false), json(123.0));
+        assert_eq!(PayloadType::S16.decode(b"-5", None, false), json(-5.0));
+    }
+
+    #[test]
+    fn applies_scale_and_sign() {
+        assert_eq!(PayloadType::U16.decode(b"100", Some(0.1), false), json(10.0));
+        assert_eq!(PayloadType::S16.decode(b"100", Some(-1.0), false), json(-100.0));
+    }
+
+    #[test]
+    fn decodes_binary_u16_big_endian() {
+        // 0x0102 = 258
+        assert_eq!(PayloadType::U16.decode(&[0x01, 0x02], None, false), json(258.0));
+    }
+
+    #[test]
+    fn decodes_binary_s16_negative() {
+        // 0xFFFF = -1 as i16
+        assert_eq!(PayloadType::S16.decode(&[0xFF, 0xFF], None, false), json(-1.0));
+    }
+
+    #[test]
+    fn swap_words_reorders_u32_words() {
+        let payload = [0x00, 0x01, 0x00, 0x02]; // words [1, 2]
+        assert_eq!(PayloadType::U32.decode(&payload, None, false), json(0x0001_0002 as f64));
+        assert_eq!(PayloadType::U32.decode(&payload, None, true), json(0x0002_0001 as f64));
+    }
+
+    #[test]
+    fn decodes_binary_f64_full_precision() {
+        let payload = std::f64::consts::PI.to_bits().to_be_bytes();
+        assert_eq!(
+            PayloadType::F64.decode(&payload, None, false),
+            json(std::f64::consts::PI)
+        );
+    }
+
+    #[test]
+    fn missing_words_default_to_zero() {
+        assert_eq!(PayloadType::U16.decode(&[], None, false), json(0.0));
+        assert_eq!(PayloadType::U32.decode(&[0x00, 0x01], None, false), json(0x0001_0000 as f64));
+    }
+
+    fn json(n: f64) -> Value {
+        Value::Number(serde_json::Number::from_f64(n).unwrap())
+    }
+}
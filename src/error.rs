@@ -0,0 +1,47 @@
+//! The crate's error type, used in place of `io::Error` and the `unwrap()`
+//! calls that used to turn a broker drop, an IPC hiccup, or a config typo
+//! into a panic.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("serialization error: {0}")]
+    Serde(#[from] serde_json::Error),
+
+    #[error("config parse error: {0}")]
+    ConfigParse(String),
+
+    #[error("mqtt client error: {0}")]
+    Mqtt(#[from] rumqttc::ClientError),
+
+    #[error("mqtt connection error: {0}")]
+    MqttConnection(#[from] rumqttc::ConnectionError),
+
+    #[error("{0} task panicked: {1}")]
+    TaskJoin(&'static str, tokio::task::JoinError),
+
+    #[error("internal channel closed: {0}")]
+    ChannelClosed(&'static str),
+
+    #[error("unknown plugin: {0}")]
+    UnknownPlugin(String),
+
+    #[error("unknown adapter: {0}")]
+    UnknownAdapter(String),
+
+    #[error("unknown device: {0}")]
+    UnknownDevice(String),
+
+    #[error("unknown property: {0}")]
+    UnknownProperty(String),
+
+    #[error("property {0} is not writable")]
+    PropertyNotWritable(String),
+
+    #[error("hook script for {0} failed: {1}")]
+    HookFailed(String, String),
+}
@@ -0,0 +1,37 @@
+//! User-configured hook scripts reacting to adapter lifecycle and pairing
+//! events, so integrators can trigger external automations (or shell out to
+//! discovery tooling for pairing) without touching this crate. Config maps
+//! an event name — `handleDeviceAdded`, `handleDeviceRemoved`,
+//! `startPairing`, `cancelPairing`, `propertyChanged` — to an executable
+//! path; [`run`] spawns it, passing context as environment variables, and
+//! turns a missing binary or non-zero exit into an `Error` rather than
+//! aborting the caller.
+
+use std::collections::HashMap;
+
+use tokio::process::Command;
+
+use crate::error::Error;
+
+/// Event name -> executable path, as declared under `hooks` in the config.
+pub type Hooks = HashMap<String, String>;
+
+/// Runs the script configured for `event`, if any, passing `context` as
+/// environment variables and waiting for it to exit. Does nothing if no
+/// script is configured for `event`.
+pub async fn run(hooks: &Hooks, event: &str, context: &[(&str, &str)]) -> Result<(), Error> {
+    let script = match hooks.get(event) {
+        Some(script) => script,
+        None => return Ok(()),
+    };
+
+    let status = Command::new(script)
+        .envs(context.iter().copied())
+        .status()
+        .await?;
+
+    if !status.success() {
+        return Err(Error::HookFailed(event.to_string(), status.to_string()));
+    }
+    Ok(())
+}
@@ -2,15 +2,31 @@ extern crate nanomsg;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate serde_yaml;
+extern crate rumqttc;
+extern crate tokio;
+
+mod config;
+mod error;
+mod hooks;
+mod mqtt;
+mod scheduler;
+mod shutdown;
 
 use std::collections::HashMap;
-use std::io::{self, Read, Write};
-use std::sync::mpsc::{channel, Sender, Receiver};
-use std::thread;
-use std::time::Duration;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
 
 use nanomsg::{Protocol, Socket};
 use serde_json::{Map, Value};
+use tokio::sync::mpsc::{self, Sender, Receiver};
+use tokio::task;
+
+use error::Error;
+
+const MQTT_BROKER_HOST: &'static str = "localhost";
+const MQTT_BROKER_PORT: u16 = 1883;
+const CONFIG_PATH: &'static str = "config.yaml";
 
 const BASE_URL: &'static str = "ipc:///tmp";
 const ADAPTER_MANAGER_URL: &'static str = "ipc:///tmp/gateway.addonManager";
@@ -137,8 +153,8 @@ struct GatewayBridge {
 
 impl GatewayBridge {
     fn new(id: &str) -> (GatewayBridge, Sender<PluginMessage>, Receiver<GatewayMessage>) {
-        let (gp_sender, gp_receiver) = channel();
-        let (pg_sender, pg_receiver) = channel();
+        let (gp_sender, gp_receiver) = mpsc::channel(32);
+        let (pg_sender, pg_receiver) = mpsc::channel(32);
         (
             GatewayBridge {
                 id: id.to_string(),
@@ -150,11 +166,14 @@ impl GatewayBridge {
         )
     }
 
-    fn run_forever(&mut self) -> Result<(), io::Error> {
+    /// Registers with the gateway's adapter manager and connects the
+    /// resulting pair socket, blocking the current thread for the duration
+    /// of the handshake.
+    fn register(id: &str) -> Result<Socket, Error> {
         let mut socket = Socket::new(Protocol::Req)?;
         let mut endpoint = socket.connect(ADAPTER_MANAGER_URL)?;
         let req = PluginRegisterMessage::RegisterPlugin {
-            plugin_id: self.id.to_string()
+            plugin_id: id.to_string()
         };
         socket.write_all(serde_json::to_string(&req)?.as_bytes())?;
         let mut rep = String::new();
@@ -162,18 +181,6 @@ impl GatewayBridge {
         endpoint.shutdown()?;
         println!("We got it! {}", rep);
         let msg: GatewayRegisterMessage = serde_json::from_str(&rep)?;
-        // open a Req channel to adapterManager
-        // send {messageType: 'registerPlugin', data: { pluginId: id }}
-        // receives
-        // {
-        //  messageType: 'registerPluginReply',
-        //  data: {
-        //    pluginId: 'pluginId-string',
-        //    ipcBaseAddr: 'gateway.plugin.xxx',
-        //  },
-        //}
-        // connect to ipcBaseAddr as pair
-        // then handle everything
 
         let ipc_base_addr = match msg {
             GatewayRegisterMessage::RegisterPluginReply {ipc_base_addr, ..} => {
@@ -182,61 +189,148 @@ impl GatewayBridge {
         };
 
         let mut socket_pair = Socket::new(Protocol::Pair)?;
-        let mut endpoint_pair = socket_pair.connect(&format!("{}/{}", BASE_URL, &ipc_base_addr))?;
+        socket_pair.connect(&format!("{}/{}", BASE_URL, &ipc_base_addr))?;
+        Ok(socket_pair)
+    }
 
-        let mut buf = Vec::new();
+    /// Blocks the current (dedicated) thread reading full messages off
+    /// `socket_pair` and forwards each decoded one to `sender`. Returns (and
+    /// drops `sender`) as soon as the socket errors, so `connect_and_serve`
+    /// sees the channel close and reports the failure instead of spinning.
+    fn read_loop(socket_pair: Arc<Mutex<Socket>>, sender: Sender<GatewayMessage>) {
+        loop {
+            let mut buf = Vec::new();
+            if let Err(err) = socket_pair.lock().unwrap().read_to_end(&mut buf) {
+                eprintln!("gateway read loop: {}", err);
+                return;
+            }
+            if let Ok(msg) = serde_json::from_slice(&buf) {
+                if sender.blocking_send(msg).is_err() {
+                    return;
+                }
+            }
+        }
+    }
 
+    /// Reconnects with exponential backoff whenever `connect_and_serve`
+    /// fails, until `shutdown` is triggered.
+    async fn run_forever(&mut self, shutdown: &shutdown::Shutdown) -> Result<(), Error> {
+        let mut backoff = shutdown::initial_backoff();
         loop {
-            let read_status = socket.nb_read_to_end(&mut buf);
-            if read_status.is_ok() {
-                match serde_json::from_slice(&buf) {
-                    Ok(msg) => {
-                        self.msg_sender.send(msg).unwrap();
-                    },
-                    _ => {
+            match self.connect_and_serve(shutdown).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    eprintln!("gateway bridge connection lost: {}; reconnecting in {:?}", err, backoff);
+                    if shutdown.sleep_or_shutdown(backoff).await {
+                        return Ok(());
                     }
+                    backoff = shutdown::next_backoff(backoff);
                 }
             }
+        }
+    }
+
+    /// Registers with the gateway once and serves requests until the
+    /// connection fails (`Err`), `shutdown` fires, or the plugin unloads
+    /// (both `Ok(())`).
+    async fn connect_and_serve(&mut self, shutdown: &shutdown::Shutdown) -> Result<(), Error> {
+        let id = self.id.clone();
+        let socket_pair = task::spawn_blocking(move || GatewayBridge::register(&id))
+            .await
+            .map_err(|err| Error::TaskJoin("gateway register", err))??;
+        let socket_pair = Arc::new(Mutex::new(socket_pair));
+
+        let (incoming_tx, mut incoming_rx) = mpsc::channel(32);
+        {
+            let socket_pair = socket_pair.clone();
+            task::spawn_blocking(move || GatewayBridge::read_loop(socket_pair, incoming_tx));
+        }
 
-            if let Ok(msg_to_send) = self.msg_receiver.try_recv() {
-                socket_pair.write_all(serde_json::to_string(&msg_to_send)?.as_bytes()).unwrap();
-                match msg_to_send {
-                    PluginMessage::PluginUnloaded {..} => {
+        let mut shutdown_rx = shutdown.subscribe();
+        loop {
+            tokio::select! {
+                incoming = incoming_rx.recv() => {
+                    match incoming {
+                        Some(msg) => self.msg_sender.send(msg).await
+                            .map_err(|_| Error::ChannelClosed("gateway message sender"))?,
+                        None => return Err(Error::ChannelClosed("gateway read loop")),
+                    }
+                }
+                outgoing = self.msg_receiver.recv() => {
+                    let msg_to_send = match outgoing {
+                        Some(msg) => msg,
+                        None => return Ok(()),
+                    };
+
+                    let payload = serde_json::to_string(&msg_to_send)?;
+                    let unloading = matches!(msg_to_send, PluginMessage::PluginUnloaded {..});
+                    let socket_pair = socket_pair.clone();
+                    task::spawn_blocking(move || {
+                        socket_pair.lock().unwrap().write_all(payload.as_bytes())
+                    }).await.map_err(|err| Error::TaskJoin("gateway write", err))??;
+
+                    if unloading {
                         println!("run_forever exiting");
-                        endpoint_pair.shutdown()?;
                         return Ok(());
                     }
-                    _ => {}
+                }
+                _ = shutdown_rx.recv() => {
+                    return Ok(());
                 }
             }
-
-            thread::sleep(Duration::from_millis(33));
         }
     }
 }
 
-fn to_io_error<E>(err: E) -> io::Error
-    where E: Into<Box<std::error::Error+Send+Sync>> {
-    io::Error::new(io::ErrorKind::Other, err)
+/// Subscribe/publish topic templates for a single property on a device.
+struct PropertyTopics {
+    subscribe: Option<String>,
+    publish: Option<String>,
 }
 
 struct Device {
     id: String,
-    props: HashMap<String, Value>
+    topics: HashMap<String, PropertyTopics>,
 }
 
 impl Device {
     fn new(id: &str) -> Device {
         Device {
             id: id.to_string(),
-            props: HashMap::new()
+            topics: HashMap::new(),
         }
     }
 }
 
 struct Adapter {
     id: String,
-    devices: HashMap<String, Device>
+    devices: HashMap<String, Device>,
+    mqtt_commands: Sender<mqtt::Command>,
+}
+
+impl Adapter {
+    async fn set_property(&self, device_id: String, property: Property) -> Result<(), Error> {
+        let device = self.devices.get(&device_id)
+            .ok_or_else(|| Error::UnknownDevice(device_id.clone()))?;
+        let topics = device.topics.get(&property.name)
+            .ok_or_else(|| Error::UnknownProperty(property.name.clone()))?;
+        let topic = topics.publish.as_ref()
+            .ok_or_else(|| Error::PropertyNotWritable(property.name.clone()))?;
+        let payload = serde_json::to_string(&property.value)?;
+        self.mqtt_commands
+            .send(mqtt::Command::Publish { topic: topic.clone(), payload })
+            .await
+            .map_err(|_| Error::ChannelClosed("mqtt commands"))
+    }
+
+    fn start_pairing(&self) -> Result<(), Error> {
+        // No discovery protocol yet; devices are declared up front.
+        Ok(())
+    }
+
+    fn cancel_pairing(&self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 struct Plugin {
@@ -244,19 +338,133 @@ struct Plugin {
     adapters: HashMap<String, Adapter>,
     sender: Sender<PluginMessage>,
     receiver: Receiver<GatewayMessage>,
+    shutdown: shutdown::Shutdown,
+    hooks: Arc<hooks::Hooks>,
 }
 
 impl Plugin {
-    fn new(id: &str, sender: Sender<PluginMessage>, receiver: Receiver<GatewayMessage>) -> Plugin {
+    fn new(
+        id: &str,
+        sender: Sender<PluginMessage>,
+        receiver: Receiver<GatewayMessage>,
+        shutdown: shutdown::Shutdown,
+        hooks: Arc<hooks::Hooks>,
+    ) -> Plugin {
         Plugin {
             id: id.to_string(),
             sender: sender,
             receiver: receiver,
             adapters: HashMap::new(),
+            shutdown,
+            hooks,
         }
     }
 
-    fn handle_msg(&self, msg: GatewayMessage) -> Result<(), io::Error> {
+    /// Spawns the hook script configured for `event`, if any, onto its own
+    /// task instead of awaiting it inline, so a slow or hung script can't
+    /// stall `run_forever`'s message handling; logs (rather than propagating)
+    /// a failure.
+    fn spawn_hook(&self, event: &'static str, context: Vec<(&'static str, String)>) {
+        let hooks = self.hooks.clone();
+        task::spawn(async move {
+            let context: Vec<(&str, &str)> = context.iter()
+                .map(|(key, value)| (*key, value.as_str()))
+                .collect();
+            if let Err(err) = hooks::run(&hooks, event, &context).await {
+                eprintln!("{}", err);
+            }
+        });
+    }
+
+    /// Builds a `Plugin` from a `Config`, subscribing every declared
+    /// property on `mqtt_bridge` and emitting `AddAdapter`/`HandleDeviceAdded`
+    /// for each adapter and device it declares.
+    async fn from_config(
+        id: &str,
+        sender: Sender<PluginMessage>,
+        receiver: Receiver<GatewayMessage>,
+        config: &config::Config,
+        mqtt_bridge: &mut mqtt::MqttBridge,
+        mqtt_commands: Sender<mqtt::Command>,
+        shutdown: shutdown::Shutdown,
+        hooks: Arc<hooks::Hooks>,
+    ) -> Result<Plugin, Error> {
+        let mut plugin = Plugin::new(id, sender, receiver, shutdown.clone(), hooks);
+        let mut poll_targets = Vec::new();
+
+        for (adapter_id, adapter_config) in &config.adapters {
+            plugin.sender.send(PluginMessage::AddAdapter {
+                plugin_id: plugin.id.clone(),
+                adapter_id: adapter_id.clone(),
+                name: adapter_config.name.clone(),
+            }).await.map_err(|_| Error::ChannelClosed("plugin message sender"))?;
+
+            let mut adapter = Adapter {
+                id: adapter_id.clone(),
+                devices: HashMap::new(),
+                mqtt_commands: mqtt_commands.clone(),
+            };
+
+            for (device_id, device_config) in &adapter_config.devices {
+                let mut device = Device::new(device_id);
+                let mut properties = Map::new();
+
+                for (prop_name, prop_config) in &device_config.properties {
+                    mqtt_bridge.subscribe(
+                        adapter_id,
+                        device_id,
+                        prop_name,
+                        &prop_config.topic,
+                        prop_config.payload_type,
+                        prop_config.scale,
+                        prop_config.swap_words,
+                        prop_config.request_topic.as_deref(),
+                    ).await?;
+
+                    device.topics.insert(prop_name.clone(), PropertyTopics {
+                        subscribe: Some(prop_config.topic.clone()),
+                        publish: prop_config.set_topic.clone(),
+                    });
+                    properties.insert(prop_name.clone(), Value::Object(Map::new()));
+
+                    if let Some(period) = &prop_config.period {
+                        poll_targets.push(scheduler::PollTarget {
+                            adapter_id: adapter_id.clone(),
+                            device_id: device_id.clone(),
+                            property: prop_name.clone(),
+                            period: scheduler::parse_period(period)?,
+                        });
+                    }
+                }
+
+                plugin.sender.send(PluginMessage::HandleDeviceAdded {
+                    plugin_id: plugin.id.clone(),
+                    adapter_id: adapter_id.clone(),
+                    id: device_id.clone(),
+                    name: device_config.name.clone(),
+                    typ: device_config.typ.clone(),
+                    properties,
+                    actions: Map::new(),
+                }).await.map_err(|_| Error::ChannelClosed("plugin message sender"))?;
+
+                plugin.spawn_hook("handleDeviceAdded", vec![
+                    ("MQTT_ADAPTER_PLUGIN_ID", plugin.id.clone()),
+                    ("MQTT_ADAPTER_ADAPTER_ID", adapter_id.clone()),
+                    ("MQTT_ADAPTER_DEVICE_ID", device_id.clone()),
+                ]);
+
+                adapter.devices.insert(device_id.clone(), device);
+            }
+
+            plugin.adapters.insert(adapter_id.clone(), adapter);
+        }
+
+        scheduler::spawn(poll_targets, mqtt_commands, shutdown);
+
+        Ok(plugin)
+    }
+
+    async fn handle_msg(&self, msg: GatewayMessage) -> Result<(), Error> {
         match msg {
             GatewayMessage::SetProperty {
                 plugin_id,
@@ -268,10 +476,15 @@ impl Plugin {
                     return Ok(())
                 }
 
-                let adapter = &self.adapters[&adapter_id];
-                adapter.set_property(device_id, property)
+                let adapter = self.adapters.get(&adapter_id)
+                    .ok_or_else(|| Error::UnknownAdapter(adapter_id.clone()))?;
+                adapter.set_property(device_id, property).await
             },
-            GatewayMessage::UnloadPlugin {..} => {
+            GatewayMessage::UnloadPlugin { plugin_id } => {
+                self.sender.send(PluginMessage::PluginUnloaded { plugin_id })
+                    .await
+                    .map_err(|_| Error::ChannelClosed("plugin message sender"))?;
+                self.shutdown.trigger();
                 Ok(())
             },
             GatewayMessage::UnloadAdapter {..} => {
@@ -286,8 +499,14 @@ impl Plugin {
                     return Ok(())
                 }
 
-                let adapter = &self.adapters[&adapter_id];
-                adapter.start_pairing()
+                let adapter = self.adapters.get(&adapter_id)
+                    .ok_or_else(|| Error::UnknownAdapter(adapter_id.clone()))?;
+                adapter.start_pairing()?;
+                self.spawn_hook("startPairing", vec![
+                    ("MQTT_ADAPTER_PLUGIN_ID", plugin_id.clone()),
+                    ("MQTT_ADAPTER_ADAPTER_ID", adapter_id.clone()),
+                ]);
+                Ok(())
             },
             GatewayMessage::CancelPairing {
                 plugin_id,
@@ -297,10 +516,25 @@ impl Plugin {
                     return Ok(())
                 }
 
-                let adapter = &self.adapters[&adapter_id];
-                adapter.cancel_pairing()
+                let adapter = self.adapters.get(&adapter_id)
+                    .ok_or_else(|| Error::UnknownAdapter(adapter_id.clone()))?;
+                adapter.cancel_pairing()?;
+                self.spawn_hook("cancelPairing", vec![
+                    ("MQTT_ADAPTER_PLUGIN_ID", plugin_id.clone()),
+                    ("MQTT_ADAPTER_ADAPTER_ID", adapter_id.clone()),
+                ]);
+                Ok(())
             },
-            GatewayMessage::RemoveThing { .. } => {
+            GatewayMessage::RemoveThing { plugin_id, adapter_id, device_id } => {
+                if plugin_id != self.id {
+                    return Ok(())
+                }
+
+                self.spawn_hook("handleDeviceRemoved", vec![
+                    ("MQTT_ADAPTER_PLUGIN_ID", plugin_id.clone()),
+                    ("MQTT_ADAPTER_ADAPTER_ID", adapter_id.clone()),
+                    ("MQTT_ADAPTER_DEVICE_ID", device_id.clone()),
+                ]);
                 Ok(())
             },
             GatewayMessage::CancelRemoveThing { .. } => {
@@ -309,38 +543,93 @@ impl Plugin {
         }
     }
 
-    fn run_forever(&mut self) -> Result<(), io::Error> {
+    async fn run_forever(&mut self) -> Result<(), Error> {
+        let mut shutdown_rx = self.shutdown.subscribe();
         loop {
-            match self.receiver.try_recv() {
-                Ok(msg) => {
-                    println!("recv: {:?}", msg);
-                    self.handle_msg(msg)?;
-                },
-                _ => {}
+            tokio::select! {
+                msg = self.receiver.recv() => {
+                    match msg {
+                        Some(msg) => {
+                            println!("recv: {:?}", msg);
+                            self.handle_msg(msg).await?;
+                        }
+                        None => return Ok(()),
+                    }
+                }
+                _ = shutdown_rx.recv() => {
+                    return Ok(());
+                }
             }
         }
     }
 }
 
-fn main() {
+#[tokio::main]
+async fn main() {
+    let config = match config::Config::load(std::path::Path::new(CONFIG_PATH)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let hooks = Arc::new(config.hooks.clone());
+    let shutdown = shutdown::Shutdown::new();
+
+    {
+        let shutdown = shutdown.clone();
+        task::spawn(async move {
+            shutdown.listen_for_signal().await;
+        });
+    }
+
     let (mut gateway_bridge, msg_sender, msg_receiver) = GatewayBridge::new("mqtt");
-    thread::spawn(move || {
-        gateway_bridge.run_forever().unwrap();
-    });
-    let mut plugin = Plugin::new("mqtt", msg_sender, msg_receiver);
-    plugin.run_forever().unwrap();
-
-    // let adapters = map from id to adapter
-    // select (nanomsg, paired bridges channel)
-    // send a start/cancel pairing to the bridge proc if requested
-    // dispatch commands to the addapters list
-    // let light_id = "1";
-
-    // let props = LightProperties {
-    //     on: true,
-    //     hue: 0,
-    //     sat: 0,
-    //     bri: 255
-    // };
-    // let _ = adapters[0].send_properties(light_id, props).unwrap();
+    {
+        let shutdown = shutdown.clone();
+        task::spawn(async move {
+            if let Err(err) = gateway_bridge.run_forever(&shutdown).await {
+                eprintln!("gateway bridge exited: {}", err);
+            }
+        });
+    }
+
+    let (mut mqtt_bridge, mqtt_commands) = mqtt::MqttBridge::new(
+        MQTT_BROKER_HOST,
+        MQTT_BROKER_PORT,
+        "mqtt-adapter",
+        "mqtt",
+        msg_sender.clone(),
+        hooks.clone(),
+    );
+
+    let mut plugin = match Plugin::from_config(
+        "mqtt",
+        msg_sender,
+        msg_receiver,
+        &config,
+        &mut mqtt_bridge,
+        mqtt_commands,
+        shutdown.clone(),
+        hooks,
+    ).await {
+        Ok(plugin) => plugin,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    {
+        let shutdown = shutdown.clone();
+        task::spawn(async move {
+            if let Err(err) = mqtt_bridge.run_forever(&shutdown).await {
+                eprintln!("mqtt bridge exited: {}", err);
+            }
+        });
+    }
+
+    if let Err(err) = plugin.run_forever().await {
+        eprintln!("plugin exited: {}", err);
+    }
+    shutdown.trigger();
 }
\ No newline at end of file
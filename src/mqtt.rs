@@ -0,0 +1,257 @@
+//! Bridges MQTT broker topics to WebThings gateway properties.
+//!
+//! The bridge subscribes to each device/property's subscribe topic and turns
+//! incoming publishes into `PluginMessage::PropertyChanged` messages sent back
+//! to the gateway. Outbound `SetProperty` requests from the gateway arrive as
+//! `Command::Publish` values over an mpsc channel and are republished to the
+//! matching device's publish topic. `Command::Refresh`, sent periodically by
+//! the [`scheduler`](crate::scheduler) module, re-emits the last value seen
+//! for a property, or, for a property with a configured request topic, asks
+//! the device for a fresh one instead of waiting on the broker to push one.
+//! The broker connection and both channels are driven concurrently by
+//! `tokio::select!` in `run_forever`, so neither side has to poll.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use serde_json::Value;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::config::PayloadType;
+use crate::error::Error;
+use crate::hooks::{self, Hooks};
+use crate::shutdown::{self, Shutdown};
+use crate::{PluginMessage, Property};
+
+/// A command sent from the plugin side to the MQTT event loop.
+pub enum Command {
+    Publish { topic: String, payload: String },
+    Refresh { adapter_id: String, device_id: String, property: String },
+}
+
+/// Identifies which gateway property a subscribed topic maps to, and how to
+/// decode the raw payload published on it.
+struct Target {
+    adapter_id: String,
+    device_id: String,
+    property: String,
+    payload_type: PayloadType,
+    scale: Option<f64>,
+    swap_words: bool,
+}
+
+pub struct MqttBridge {
+    client: AsyncClient,
+    eventloop: EventLoop,
+    commands: Receiver<Command>,
+    targets: HashMap<String, Target>,
+    /// Last decoded value seen for each (adapter, device, property), so a
+    /// scheduled `Command::Refresh` has something to re-emit even if the
+    /// broker hasn't published since the last refresh.
+    last_values: HashMap<(String, String, String), Value>,
+    /// Request topic for each (adapter, device, property) that needs one
+    /// actively asked for rather than pushed. A scheduled `Command::Refresh`
+    /// publishes here instead of replaying `last_values`; the device's reply
+    /// arrives through the usual subscribed topic.
+    request_topics: HashMap<(String, String, String), String>,
+    gateway_sender: Sender<PluginMessage>,
+    plugin_id: String,
+    hooks: Arc<Hooks>,
+}
+
+impl MqttBridge {
+    /// Creates a client for `host`:`port` as `client_id` and returns the
+    /// bridge along with the `Sender` adapters use to request outbound
+    /// publishes. The broker connection is made once `run_forever` starts
+    /// polling the event loop.
+    pub fn new(
+        host: &str,
+        port: u16,
+        client_id: &str,
+        plugin_id: &str,
+        gateway_sender: Sender<PluginMessage>,
+        hooks: Arc<Hooks>,
+    ) -> (MqttBridge, Sender<Command>) {
+        let options = MqttOptions::new(client_id, host, port);
+        let (client, eventloop) = AsyncClient::new(options, 10);
+        let (cmd_sender, cmd_receiver) = mpsc::channel(32);
+        (
+            MqttBridge {
+                client,
+                eventloop,
+                commands: cmd_receiver,
+                targets: HashMap::new(),
+                last_values: HashMap::new(),
+                request_topics: HashMap::new(),
+                gateway_sender,
+                plugin_id: plugin_id.to_string(),
+                hooks,
+            },
+            cmd_sender,
+        )
+    }
+
+    /// Spawns the `propertyChanged` hook, if configured, onto its own task
+    /// instead of awaiting it inline, so a slow or hung script can't stall
+    /// `poll_until_error`'s `select!` loop; logs (rather than propagating) a
+    /// failure.
+    fn spawn_property_changed_hook(&self, adapter_id: &str, device_id: &str, property: &Property) {
+        let hooks = self.hooks.clone();
+        let plugin_id = self.plugin_id.clone();
+        let adapter_id = adapter_id.to_string();
+        let device_id = device_id.to_string();
+        let property_name = property.name.clone();
+        let value = property.value.to_string();
+        tokio::spawn(async move {
+            if let Err(err) = hooks::run(&hooks, "propertyChanged", &[
+                ("MQTT_ADAPTER_PLUGIN_ID", plugin_id.as_str()),
+                ("MQTT_ADAPTER_ADAPTER_ID", adapter_id.as_str()),
+                ("MQTT_ADAPTER_DEVICE_ID", device_id.as_str()),
+                ("MQTT_ADAPTER_PROPERTY_NAME", property_name.as_str()),
+                ("MQTT_ADAPTER_PROPERTY_VALUE", value.as_str()),
+            ]).await {
+                eprintln!("{}", err);
+            }
+        });
+    }
+
+    /// Subscribes to `topic` and records that incoming publishes on it
+    /// represent `property` on `adapter_id`/`device_id`, to be decoded as
+    /// `payload_type` with the given `scale` and `swap_words`. If
+    /// `request_topic` is given, a scheduled `Command::Refresh` for this
+    /// property publishes to it instead of replaying the last seen value.
+    pub async fn subscribe(
+        &mut self,
+        adapter_id: &str,
+        device_id: &str,
+        property: &str,
+        topic: &str,
+        payload_type: PayloadType,
+        scale: Option<f64>,
+        swap_words: bool,
+        request_topic: Option<&str>,
+    ) -> Result<(), Error> {
+        self.client.subscribe(topic, QoS::AtLeastOnce).await?;
+        self.targets.insert(
+            topic.to_string(),
+            Target {
+                adapter_id: adapter_id.to_string(),
+                device_id: device_id.to_string(),
+                property: property.to_string(),
+                payload_type,
+                scale,
+                swap_words,
+            },
+        );
+        if let Some(request_topic) = request_topic {
+            self.request_topics.insert(
+                (adapter_id.to_string(), device_id.to_string(), property.to_string()),
+                request_topic.to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Drives the MQTT connection until `shutdown` fires, reconnecting with
+    /// exponential backoff whenever the broker connection is lost.
+    pub async fn run_forever(mut self, shutdown: &Shutdown) -> Result<(), Error> {
+        let mut backoff = shutdown::initial_backoff();
+        loop {
+            match self.poll_until_error(shutdown).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    eprintln!("mqtt connection lost: {}; reconnecting in {:?}", err, backoff);
+                    if shutdown.sleep_or_shutdown(backoff).await {
+                        return Ok(());
+                    }
+                    backoff = shutdown::next_backoff(backoff);
+                }
+            }
+        }
+    }
+
+    /// Publishes any queued `Command`s and forwards matched incoming
+    /// publishes to the gateway until the broker connection errors (`Err`)
+    /// or `shutdown` fires (`Ok(())`). The command channel and the broker
+    /// event loop are polled concurrently, so a pending publish never waits
+    /// on an idle broker (or vice versa); `rumqttc` reconnects the
+    /// underlying socket on its own as long as `poll` keeps being called.
+    async fn poll_until_error(&mut self, shutdown: &Shutdown) -> Result<(), Error> {
+        let mut shutdown_rx = shutdown.subscribe();
+        loop {
+            tokio::select! {
+                cmd = self.commands.recv() => {
+                    match cmd {
+                        Some(Command::Publish { topic, payload }) => {
+                            self.client
+                                .publish(topic, QoS::AtLeastOnce, false, payload)
+                                .await?;
+                        }
+                        Some(Command::Refresh { adapter_id, device_id, property }) => {
+                            let key = (adapter_id.clone(), device_id.clone(), property.clone());
+                            if let Some(request_topic) = self.request_topics.get(&key).cloned() {
+                                self.client
+                                    .publish(request_topic, QoS::AtLeastOnce, false, "")
+                                    .await?;
+                            } else if let Some(value) = self.last_values.get(&key).cloned() {
+                                let property = Property { name: property, value };
+                                self.spawn_property_changed_hook(&adapter_id, &device_id, &property);
+                                self.gateway_sender
+                                    .send(PluginMessage::PropertyChanged {
+                                        plugin_id: self.plugin_id.clone(),
+                                        adapter_id,
+                                        device_id,
+                                        property,
+                                    })
+                                    .await
+                                    .map_err(|_| Error::ChannelClosed("gateway sender"))?;
+                            }
+                        }
+                        None => return Ok(()),
+                    }
+                }
+                notification = self.eventloop.poll() => {
+                    let notification = notification?;
+                    if let Event::Incoming(Packet::ConnAck(_)) = notification {
+                        // A fresh connection means the broker has dropped any
+                        // subscription state it held for us (clean-session
+                        // semantics), so every stored target must be
+                        // re-subscribed or we'll stop hearing about it forever.
+                        let topics: Vec<String> = self.targets.keys().cloned().collect();
+                        for topic in topics {
+                            self.client.subscribe(topic, QoS::AtLeastOnce).await?;
+                        }
+                    }
+                    if let Event::Incoming(Packet::Publish(publish)) = notification {
+                        if let Some(target) = self.targets.get(&publish.topic) {
+                            let value = target.payload_type.decode(
+                                &publish.payload,
+                                target.scale,
+                                target.swap_words,
+                            );
+                            self.last_values.insert(
+                                (target.adapter_id.clone(), target.device_id.clone(), target.property.clone()),
+                                value.clone(),
+                            );
+                            let property = Property { name: target.property.clone(), value };
+                            self.spawn_property_changed_hook(&target.adapter_id, &target.device_id, &property);
+                            self.gateway_sender
+                                .send(PluginMessage::PropertyChanged {
+                                    plugin_id: self.plugin_id.clone(),
+                                    adapter_id: target.adapter_id.clone(),
+                                    device_id: target.device_id.clone(),
+                                    property,
+                                })
+                                .await
+                                .map_err(|_| Error::ChannelClosed("gateway sender"))?;
+                        }
+                    }
+                }
+                _ = shutdown_rx.recv() => {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
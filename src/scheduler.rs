@@ -0,0 +1,132 @@
+//! Periodic polling for properties that can't rely on a broker push alone
+//! (e.g. one fronted by a request/response topic, or a sensor that only
+//! needs checking occasionally). Each property's `period` config string is
+//! parsed into a `Duration`; properties sharing an identical period are
+//! driven by a single ticking task rather than one per property, so a
+//! hundred `30s` properties don't spawn a hundred timers.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::sync::mpsc::Sender;
+use tokio::task;
+
+use crate::error::Error;
+use crate::mqtt::Command;
+use crate::shutdown::Shutdown;
+
+/// A single property to refresh on a schedule.
+pub struct PollTarget {
+    pub adapter_id: String,
+    pub device_id: String,
+    pub property: String,
+    pub period: Duration,
+}
+
+/// Parses a human duration like `"3s"`, `"500ms"` or `"1m"` into a `Duration`.
+pub fn parse_period(text: &str) -> Result<Duration, Error> {
+    let text = text.trim();
+    let split_at = text
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| Error::ConfigParse(format!("invalid period {:?}: missing unit", text)))?;
+    let (value, unit) = text.split_at(split_at);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| Error::ConfigParse(format!("invalid period {:?}: not a number", text)))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(value * 60)),
+        "h" => Ok(Duration::from_secs(value * 3600)),
+        other => Err(Error::ConfigParse(format!(
+            "invalid period {:?}: unknown unit {:?}",
+            text, other
+        ))),
+    }
+}
+
+/// Spawns one ticking task per distinct period among `targets`, each sending
+/// `Command::Refresh` for every property sharing that period until `shutdown`
+/// fires.
+pub fn spawn(targets: Vec<PollTarget>, commands: Sender<Command>, shutdown: Shutdown) {
+    let mut by_period: HashMap<Duration, Vec<PollTarget>> = HashMap::new();
+    for target in targets {
+        by_period.entry(target.period).or_default().push(target);
+    }
+
+    for (period, targets) in by_period {
+        let commands = commands.clone();
+        let shutdown = shutdown.clone();
+        task::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            let mut shutdown_rx = shutdown.subscribe();
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        for target in &targets {
+                            let cmd = Command::Refresh {
+                                adapter_id: target.adapter_id.clone(),
+                                device_id: target.device_id.clone(),
+                                property: target.property.clone(),
+                            };
+                            if commands.send(cmd).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    _ = shutdown_rx.recv() => return,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_milliseconds() {
+        assert_eq!(parse_period("500ms").unwrap(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn parses_seconds() {
+        assert_eq!(parse_period("3s").unwrap(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn parses_minutes() {
+        assert_eq!(parse_period("1m").unwrap(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn parses_hours() {
+        assert_eq!(parse_period("2h").unwrap(), Duration::from_secs(7200));
+    }
+
+    #[test]
+    fn trims_surrounding_whitespace() {
+        assert_eq!(parse_period("  10s  ").unwrap(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn rejects_missing_unit() {
+        assert!(parse_period("10").is_err());
+    }
+
+    #[test]
+    fn rejects_missing_number() {
+        assert!(parse_period("s").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_unit() {
+        assert!(parse_period("10d").is_err());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(parse_period("not-a-duration").is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! Coordinates a clean shutdown across the bridge's async tasks and the
+//! exponential backoff used when reconnecting after a transport error.
+
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Doubles `current`, capped at `MAX_BACKOFF`. Callers start from
+/// `INITIAL_BACKOFF` after the first failure.
+pub fn next_backoff(current: Duration) -> Duration {
+    std::cmp::min(current * 2, MAX_BACKOFF)
+}
+
+pub fn initial_backoff() -> Duration {
+    INITIAL_BACKOFF
+}
+
+/// A broadcast signal telling every task to tear down and return. Cloning a
+/// `Shutdown` shares the same signal; `trigger` can be called from any of
+/// them (SIGINT, an `UnloadPlugin` message, ...).
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: broadcast::Sender<()>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (sender, _) = broadcast::channel(1);
+        Shutdown { sender }
+    }
+
+    /// Signals every subscriber to shut down. Safe to call more than once.
+    pub fn trigger(&self) {
+        let _ = self.sender.send(());
+    }
+
+    /// Subscribes to the shutdown signal. Callers driving a `tokio::select!`
+    /// loop across several iterations must subscribe once before the loop
+    /// and reuse the same receiver for every iteration: a broadcast receiver
+    /// only observes values sent after it subscribes, so resubscribing on
+    /// every iteration (e.g. calling this inside the loop body) can miss a
+    /// `trigger()` that lands in the gap between iterations, since each
+    /// `trigger` sends a single value and nothing records that it fired.
+    pub fn subscribe(&self) -> broadcast::Receiver<()> {
+        self.sender.subscribe()
+    }
+
+    /// Waits until either `delay` elapses or shutdown is triggered, whichever
+    /// is first. Returns `true` if shutdown fired during the wait, so
+    /// reconnect loops can stop retrying instead of sleeping out the backoff.
+    pub async fn sleep_or_shutdown(&self, delay: Duration) -> bool {
+        let mut rx = self.subscribe();
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => false,
+            _ = rx.recv() => true,
+        }
+    }
+
+    /// Waits for SIGINT (Ctrl-C) and triggers shutdown when it arrives.
+    pub async fn listen_for_signal(&self) {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            println!("received SIGINT, shutting down");
+            self.trigger();
+        }
+    }
+}